@@ -0,0 +1,485 @@
+//! Core compilation and merge logic shared by the `cairo1-compile` binary.
+//!
+//! The functions here accept and return whole `serde_json::Value`s so that
+//! other Rust tools (test runners, proving pipelines) can produce a
+//! [`ProgramWithArgs`] in-process without shelling out to the CLI.
+
+use std::{
+    fmt,
+    fs::File,
+    io::BufReader,
+    path::Path,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::de::DeserializeOwned;
+use cairo_lang_compiler::{
+    compile_prepared_db, db::RootDatabase, project::setup_project, CompilerConfig,
+};
+use cairo_lang_filesystem::ids::Directory;
+use cairo_lang_project::{ProjectConfig, ProjectConfigContent};
+use cairo_lang_sierra::program::{Program, VersionedProgram};
+use cairo_lang_sierra_to_casm::{compiler::compile as sierra_to_casm, metadata::calc_metadata};
+use cairo_lang_starknet_classes::{
+    casm_contract_class::CasmContractClass, contract_class::ContractClass,
+};
+use cairo_lang_utils::ordered_hash_map::OrderedHashMap;
+use scarb_metadata::MetadataCommand;
+use serde::Serialize;
+use smol_str::SmolStr;
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default)]
+pub enum Layout {
+    Plain,
+    Small,
+    Dex,
+    #[default]
+    Recursive,
+    Starknet,
+    StarknetWithKeccak,
+    RecursiveWithPoseidon,
+    RecursiveLargeOutput,
+    AllSolidity,
+    AllCairo,
+    Dynamic,
+}
+
+impl Layout {
+    /// The builtins this layout makes available to the program. A program that
+    /// uses any builtin outside this set is rejected by the prover, so we check
+    /// it at merge time (see [`merge_program`]).
+    fn allowed_builtins(&self) -> &'static [&'static str] {
+        match self {
+            Layout::Plain => &["output"],
+            Layout::Small => &["output", "pedersen", "range_check", "ecdsa"],
+            Layout::Dex => &["output", "pedersen", "range_check", "ecdsa"],
+            Layout::Recursive => &["output", "pedersen", "range_check", "bitwise"],
+            Layout::RecursiveLargeOutput => &["output", "pedersen", "range_check", "bitwise"],
+            Layout::RecursiveWithPoseidon => {
+                &["output", "pedersen", "range_check", "bitwise", "poseidon"]
+            }
+            Layout::Starknet => &[
+                "output", "pedersen", "range_check", "ecdsa", "bitwise", "ec_op", "poseidon",
+            ],
+            Layout::StarknetWithKeccak => &[
+                "output", "pedersen", "range_check", "ecdsa", "bitwise", "ec_op", "keccak",
+                "poseidon",
+            ],
+            Layout::AllSolidity => &[
+                "output", "pedersen", "range_check", "ecdsa", "bitwise", "ec_op", "keccak",
+                "poseidon",
+            ],
+            Layout::AllCairo | Layout::Dynamic => &[
+                "output",
+                "pedersen",
+                "range_check",
+                "ecdsa",
+                "bitwise",
+                "ec_op",
+                "keccak",
+                "poseidon",
+                "range_check96",
+                "add_mod",
+                "mul_mod",
+            ],
+        }
+    }
+}
+
+/// Maps a Sierra builtin type name to its canonical layout builtin name,
+/// returning `None` for non-builtin types.
+fn builtin_for_type(type_name: &str) -> Option<&'static str> {
+    match type_name {
+        "RangeCheck" => Some("range_check"),
+        "RangeCheck96" => Some("range_check96"),
+        "Bitwise" => Some("bitwise"),
+        "Pedersen" => Some("pedersen"),
+        "Poseidon" => Some("poseidon"),
+        "EcOp" => Some("ec_op"),
+        "Ecdsa" => Some("ecdsa"),
+        "AddMod" => Some("add_mod"),
+        "MulMod" => Some("mul_mod"),
+        // `SegmentArena` is supplied by the runner for any layout (dictionaries
+        // pull it in even under `recursive`/`starknet`), so it is deliberately
+        // not treated as a layout-gated builtin.
+        _ => None,
+    }
+}
+
+/// Collects the layout builtins a Sierra [`Program`] requires by scanning its
+/// type declarations.
+fn required_builtins(program: &Program) -> Vec<&'static str> {
+    let mut builtins = Vec::new();
+    for decl in &program.type_declarations {
+        if let Some(builtin) = builtin_for_type(decl.long_id.generic_id.0.as_str()) {
+            if !builtins.contains(&builtin) {
+                builtins.push(builtin);
+            }
+        }
+    }
+    builtins
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ProgramWithArgs {
+    pub program: Program,
+    pub program_input: serde_json::Value,
+    pub layout: String,
+}
+
+/// Compiles the Cairo source at `path` to a Sierra [`Program`].
+///
+/// `path` may be a single `.cairo` file or a directory containing a
+/// `cairo_project.toml` or `Scarb.toml` (see [`build_database`]). The whole
+/// crate is compiled; selecting a single entry point out of a multi-target
+/// crate is not supported (narrowing a compiled Sierra program correctly would
+/// require a full reachability pass over its statements and libfuncs).
+pub fn compile_sierra_program(path: &Path) -> Result<Program> {
+    let compiler_config = CompilerConfig {
+        replace_ids: true,
+        ..CompilerConfig::default()
+    };
+    let mut db = build_database(path)?;
+    let main_crate_ids = setup_project(&mut db, path)
+        .with_context(|| format!("failed to set up project at {}", path.display()))?;
+    Ok(compile_prepared_db(&mut db, main_crate_ids, compiler_config)?.program)
+}
+
+/// Reads and compiles the source at `path`, see [`compile_sierra_program`].
+pub fn compile_sierra_file(path: &Path) -> Result<Program> {
+    compile_sierra_program(path)
+}
+
+/// Wraps a Sierra `program`, its `input` and the chosen `layout` into the
+/// merged JSON envelope the prover consumes.
+pub fn merge_program(
+    program: Program,
+    input: serde_json::Value,
+    layout: Layout,
+) -> Result<serde_json::Value> {
+    let allowed = layout.allowed_builtins();
+    for builtin in required_builtins(&program) {
+        if !allowed.contains(&builtin) {
+            bail!(
+                "builtin `{builtin}` is not available in layout `{layout}`; \
+                 `{layout}` provides: {}",
+                allowed.join(", ")
+            );
+        }
+    }
+    let merged = ProgramWithArgs {
+        program,
+        program_input: input,
+        layout: layout.to_string(),
+    };
+    serde_json::to_value(&merged).context("failed to serialize merged program")
+}
+
+/// Reads a Sierra [`Program`] from a JSON file.
+///
+/// Parse failures carry the exact field path that failed (e.g.
+/// `funcs[3].signature`). Fields the schema does not recognize are
+/// reported: a warning by default, or a hard error when `strict` is set.
+pub fn read_sierra_program(
+    path: &Path,
+    strict: bool,
+    warn: &mut dyn FnMut(&str),
+) -> Result<Program> {
+    let (program, ignored) = read_json_typed(path, strict)?;
+    // In strict mode unrecognized fields already errored inside `read_json_typed`;
+    // here we only report the non-strict warning, leaving how it is rendered
+    // (plain text vs. JSON envelope) to the caller that knows the output mode.
+    if !ignored.is_empty() {
+        warn(&format!(
+            "unrecognized field(s) in {}: {}",
+            path.display(),
+            ignored.join(", ")
+        ));
+    }
+    Ok(program)
+}
+
+/// Reads an arbitrary JSON value from a file; used for the free-form program
+/// input and for the Sierra artifact fed to [`compile_casm_json`]. Parse
+/// failures carry the failing field path.
+///
+/// `strict` is threaded through for symmetry with [`read_sierra_program`], but
+/// unknown-field detection cannot fire here: the target is
+/// [`serde_json::Value`], which accepts every key, so there is no schema to
+/// compare against. Only [`read_sierra_program`], which deserializes into the
+/// typed [`Program`], reports unrecognized fields.
+pub fn read_json(path: &Path, strict: bool) -> Result<serde_json::Value> {
+    let (value, _ignored) = read_json_typed(path, strict)?;
+    Ok(value)
+}
+
+/// Reads the program input JSON.
+///
+/// The input is free-form [`serde_json::Value`], so per-field unknown-key
+/// detection does not apply. Under `strict`, the one schema-independent check we
+/// can make is enforced: a real program input is a list of arguments or an
+/// argument object, so a bare scalar or `null` at the top level — the usual
+/// shape of a wrong or truncated file — is rejected here rather than deep in the
+/// prover.
+pub fn read_program_input(path: &Path, strict: bool) -> Result<serde_json::Value> {
+    let input = read_json(path, strict)?;
+    if strict {
+        validate_program_input(&input)
+            .with_context(|| format!("invalid program input in {}", path.display()))?;
+    }
+    Ok(input)
+}
+
+/// Rejects program inputs whose top level is neither an array nor an object.
+fn validate_program_input(input: &serde_json::Value) -> Result<()> {
+    if input.is_array() || input.is_object() {
+        return Ok(());
+    }
+    bail!(
+        "expected a JSON array of arguments or an argument object, found {}",
+        json_kind(input)
+    );
+}
+
+/// A human-readable name for a JSON value's kind, for error messages.
+fn json_kind(value: &serde_json::Value) -> &'static str {
+    match value {
+        serde_json::Value::Null => "null",
+        serde_json::Value::Bool(_) => "a boolean",
+        serde_json::Value::Number(_) => "a number",
+        serde_json::Value::String(_) => "a string",
+        serde_json::Value::Array(_) => "an array",
+        serde_json::Value::Object(_) => "an object",
+    }
+}
+
+/// Deserializes `T` from a JSON file, returning both the value and the field
+/// paths the schema ignored. Unrecognized keys are only ever surfaced for typed
+/// targets; a [`serde_json::Value`] target captures every key and so reports
+/// none. Under `strict` any ignored key is turned into an error here; otherwise
+/// the paths are handed back for the caller to report as it sees fit.
+fn read_json_typed<T: DeserializeOwned>(path: &Path, strict: bool) -> Result<(T, Vec<String>)> {
+    let file = File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    let mut deserializer = serde_json::Deserializer::from_reader(BufReader::new(file));
+    let mut track = serde_path_to_error::Track::new();
+    let tracked = serde_path_to_error::Deserializer::new(&mut deserializer, &mut track);
+
+    let mut ignored = Vec::new();
+    let value: T = serde_ignored::deserialize(tracked, |field_path| {
+        ignored.push(field_path.to_string());
+    })
+    .map_err(|err| {
+        anyhow!(
+            "failed to parse {} at `{}`: {}",
+            path.display(),
+            track.path(),
+            err
+        )
+    })?;
+    deserializer
+        .end()
+        .with_context(|| format!("trailing data in {}", path.display()))?;
+
+    if strict && !ignored.is_empty() {
+        bail!(
+            "unrecognized field(s) in {}: {}",
+            path.display(),
+            ignored.join(", ")
+        );
+    }
+    Ok((value, ignored))
+}
+
+/// Compiles a Sierra artifact to CASM JSON.
+///
+/// The input may be a raw [`Program`] (optionally wrapped in a
+/// [`VersionedProgram`]) or a Starknet [`ContractClass`]. Sierra in the wild is
+/// serialized under several compiler versions; rather than assume the current
+/// one, the artifact's own `version` tag selects how it is decoded
+/// ([`parse_sierra_program`]) and the result is upgraded to the representation
+/// the linked backend lowers, so older artifacts still compile.
+pub fn compile_casm_json(value: serde_json::Value) -> Result<serde_json::Value> {
+    // A contract class carries its own `sierra_program` field; a bare program
+    // does not. Try the richer shape first.
+    if value.get("sierra_program").is_some() {
+        let contract: ContractClass = serde_json::from_value(value)
+            .context("failed to parse Sierra contract class")?;
+        let casm = CasmContractClass::from_contract_class(contract, true, usize::MAX)
+            .context("failed to compile contract class to CASM")?;
+        return serde_json::to_value(&casm).context("failed to serialize CASM contract class");
+    }
+
+    let program = parse_sierra_program(value)?;
+    let metadata = calc_metadata(&program, Default::default())
+        .context("failed to compute Sierra metadata")?;
+    let casm = sierra_to_casm(&program, &metadata, Default::default())
+        .context("failed to compile Sierra program to CASM")?;
+    serde_json::to_value(&casm).context("failed to serialize CASM program")
+}
+
+/// Parses a raw Sierra [`Program`] from any serialized version.
+///
+/// A `version`-tagged payload is decoded through [`VersionedProgram`], whose
+/// variants enumerate every encoding the linked compiler understands, and then
+/// `into_v1`-upgraded to the in-memory representation the backend lowers — this
+/// is the version dispatch: newer/older tagged artifacts take their own decode
+/// path instead of being force-read as the current one. An untagged payload
+/// predates the wrapper and is read directly. Versions the compiler does not
+/// know about surface as a decode error naming the version.
+fn parse_sierra_program(value: serde_json::Value) -> Result<Program> {
+    if let Some(version) = value.get("version").cloned() {
+        let versioned: VersionedProgram = serde_json::from_value(value)
+            .with_context(|| format!("unsupported Sierra program version {version}"))?;
+        return Ok(versioned.into_v1().context("failed to normalize Sierra program")?.program);
+    }
+    serde_json::from_value(value).context("failed to parse Sierra program")
+}
+
+/// Builds the database for `path`.
+///
+/// A directory carrying a `Scarb.toml` resolves its crate roots and
+/// dependencies (corelib included) from the Scarb manifest via `scarb
+/// metadata`; a directory carrying only a `cairo_project.toml` loads that
+/// manifest directly; a single file falls back to `detect_corelib`.
+fn build_database(path: &Path) -> Result<RootDatabase> {
+    let mut builder = RootDatabase::builder();
+    builder.skip_auto_withdraw_gas();
+    if path.is_dir() && path.join("Scarb.toml").exists() {
+        builder.with_project_config(scarb_project_config(path)?);
+    } else if path.is_dir() && path.join("cairo_project.toml").exists() {
+        let config = ProjectConfig::from_directory(path)
+            .with_context(|| format!("failed to load cairo project at {}", path.display()))?;
+        builder.with_project_config(config);
+    } else {
+        builder.detect_corelib();
+    }
+    builder.build()
+}
+
+/// Resolves a Scarb project into a [`ProjectConfig`] by asking `scarb metadata`
+/// for the compilation unit and mapping each of its components to a crate root,
+/// treating the `core` component as the corelib.
+fn scarb_project_config(path: &Path) -> Result<ProjectConfig> {
+    let metadata = MetadataCommand::new()
+        .manifest_path(path.join("Scarb.toml"))
+        .inherit_stderr()
+        .exec()
+        .with_context(|| format!("failed to run `scarb metadata` for {}", path.display()))?;
+
+    let unit = metadata
+        .compilation_units
+        .into_iter()
+        .next()
+        .with_context(|| format!("Scarb project at {} has no compilation units", path.display()))?;
+
+    let mut crate_roots = OrderedHashMap::default();
+    let mut corelib = None;
+    for component in unit.components {
+        let root = component
+            .source_path
+            .parent()
+            .with_context(|| format!("component `{}` has no crate root", component.name))?
+            .to_path_buf();
+        if component.name == "core" {
+            corelib = Some(Directory::Real(root.into()));
+        } else {
+            crate_roots.insert(SmolStr::from(component.name), root.into());
+        }
+    }
+
+    Ok(ProjectConfig {
+        base_path: path.to_path_buf(),
+        corelib,
+        content: ProjectConfigContent {
+            crate_roots,
+            crates_config: Default::default(),
+        },
+    })
+}
+
+impl fmt::Display for Layout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Layout::Plain => "plain",
+            Layout::Small => "small",
+            Layout::Dex => "dex",
+            Layout::Recursive => "recursive",
+            Layout::Starknet => "starknet",
+            Layout::StarknetWithKeccak => "starknet_with_keccak",
+            Layout::RecursiveWithPoseidon => "recursive_with_poseidon",
+            Layout::RecursiveLargeOutput => "recursive_large_output",
+            Layout::AllSolidity => "all_solidity",
+            Layout::AllCairo => "all_cairo",
+            Layout::Dynamic => "dynamic",
+        };
+        write!(f, "{name}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn untagged_payload_takes_the_direct_decode_path() {
+        // An untagged empty program is not a valid `Program`, but it must fail
+        // on the program schema, not on the versioned-wrapper path.
+        let err = parse_sierra_program(json!({})).unwrap_err().to_string();
+        assert!(
+            err.contains("parse Sierra program"),
+            "untagged payloads should decode directly: {err}"
+        );
+    }
+
+    #[test]
+    fn tagged_payload_takes_the_versioned_decode_path() {
+        // A `version`-tagged payload routes through `VersionedProgram`; a bogus
+        // body fails there, and the error names the offending version.
+        let err = parse_sierra_program(json!({"version": "9.9.9"}))
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.contains("9.9.9"),
+            "versioned decode errors should name the version: {err}"
+        );
+    }
+
+    #[test]
+    fn program_input_accepts_arrays_and_objects() {
+        assert!(validate_program_input(&json!([1, 2, 3])).is_ok());
+        assert!(validate_program_input(&json!({"a": 1})).is_ok());
+    }
+
+    #[test]
+    fn program_input_rejects_bare_scalars() {
+        let err = validate_program_input(&json!(42)).unwrap_err().to_string();
+        assert!(err.contains("a number"), "error should name the kind: {err}");
+        assert!(validate_program_input(&json!(null)).is_err());
+    }
+
+    #[test]
+    fn builtin_type_names_map_to_canonical_names() {
+        assert_eq!(builtin_for_type("Bitwise"), Some("bitwise"));
+        assert_eq!(builtin_for_type("EcOp"), Some("ec_op"));
+        assert_eq!(builtin_for_type("Poseidon"), Some("poseidon"));
+        // Non-builtin and runner-supplied types are not gated.
+        assert_eq!(builtin_for_type("GasBuiltin"), None);
+        assert_eq!(builtin_for_type("SegmentArena"), None);
+    }
+
+    #[test]
+    fn recursive_permits_bitwise_but_not_ec_op() {
+        let allowed = Layout::Recursive.allowed_builtins();
+        assert!(allowed.contains(&"bitwise"));
+        assert!(!allowed.contains(&"ec_op"));
+    }
+
+    #[test]
+    fn starknet_permits_the_full_starknet_builtin_set() {
+        let allowed = Layout::Starknet.allowed_builtins();
+        for builtin in ["pedersen", "range_check", "ecdsa", "bitwise", "ec_op", "poseidon"] {
+            assert!(allowed.contains(&builtin), "starknet should allow {builtin}");
+        }
+    }
+}
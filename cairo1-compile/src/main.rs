@@ -1,20 +1,68 @@
 use std::{
-    fmt,
     fs::File,
-    io::{BufReader, Write},
+    io::Write,
     path::{Path, PathBuf},
 };
 
-use cairo_lang_compiler::{
-    compile_prepared_db, db::RootDatabase, project::setup_project, CompilerConfig,
+use anyhow::{Error, Result};
+use cairo1_compile::{
+    compile_casm_json, compile_sierra_program, merge_program, read_json, read_program_input,
+    read_sierra_program, Layout,
 };
-use cairo_lang_sierra::program::Program;
 use clap::{Parser, ValueHint};
-use serde::Serialize;
+use serde_json::json;
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default)]
+enum OutputFormat {
+    /// Raw strings to stdout and human-readable errors to stderr.
+    #[default]
+    Text,
+    /// A JSON envelope describing the result or error.
+    Json,
+}
+
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+struct Cli {
+    /// How successful results and errors are rendered.
+    #[clap(long, value_enum, default_value_t, global = true)]
+    output_format: OutputFormat,
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Parser, Debug)]
+enum Command {
+    Compile(CompileArgs),
+    Merge(MergeArgs),
+    CompileCasm(CompileCasmArgs),
+}
+
+impl Command {
+    /// The stage name reported in the JSON error envelope.
+    fn stage(&self) -> &'static str {
+        match self {
+            Command::Compile(_) => "compile",
+            Command::Merge(_) => "merge",
+            Command::CompileCasm(_) => "compile-casm",
+        }
+    }
+
+    /// The primary input path the stage operates on, for error reporting.
+    fn path(&self) -> &Path {
+        match self {
+            Command::Compile(args) => &args.program,
+            Command::Merge(args) => &args.sierra,
+            Command::CompileCasm(args) => &args.sierra,
+        }
+    }
+}
 
 #[derive(Parser, Debug)]
 struct CompileArgs {
-    #[clap(value_parser, value_hint=ValueHint::FilePath, value_name = "FILE")]
+    /// A single `.cairo` file, or a directory containing a `cairo_project.toml`
+    /// or `Scarb.toml` describing a whole crate.
+    #[clap(value_parser, value_hint=ValueHint::AnyPath, value_name = "PATH")]
     program: PathBuf,
     #[clap(short, long, value_name = "FILE")]
     output: Option<PathBuf>,
@@ -30,91 +78,108 @@ struct MergeArgs {
     output: Option<PathBuf>,
     #[clap(short, long, value_enum)]
     layout: Option<Layout>,
-}
-
-#[derive(clap::ValueEnum, Clone, Debug, Default)]
-enum Layout {
-    #[default]
-    Recursive,
+    /// Treat unrecognized fields in the sierra JSON as errors rather than
+    /// warnings, and reject an input JSON whose top level is neither an array
+    /// nor an object. Unknown keys inside the input JSON are not detected.
+    #[clap(long)]
+    strict: bool,
 }
 
 #[derive(Parser, Debug)]
-#[clap(author, version, about, long_about = None)]
-enum Args {
-    Compile(CompileArgs),
-    Merge(MergeArgs),
+struct CompileCasmArgs {
+    /// Sierra JSON, either a raw `Program` or a contract class.
+    #[clap(value_parser, value_hint=ValueHint::FilePath, value_name = "FILE")]
+    sierra: PathBuf,
+    #[clap(short, long, value_name = "FILE")]
+    output: Option<PathBuf>,
 }
 
 fn main() {
-    match Args::parse() {
-        Args::Compile(args) => compile(args),
-        Args::Merge(args) => merge(args),
+    let cli = Cli::parse();
+    let format = cli.output_format;
+    let stage = cli.command.stage();
+    let path = cli.command.path().to_path_buf();
+
+    let result = match cli.command {
+        Command::Compile(args) => compile(args, format),
+        Command::Merge(args) => merge(args, format),
+        Command::CompileCasm(args) => compile_casm(args, format),
+    };
+    if let Err(err) = result {
+        emit_error(format, stage, &path, &err);
+        std::process::exit(1);
     }
 }
 
-#[derive(Debug, Clone, Serialize)]
-struct ProgramWithArgs {
-    program: Program,
-    program_input: serde_json::Value,
-    layout: String,
+fn merge(args: MergeArgs, format: OutputFormat) -> Result<()> {
+    let mut warn = |message: &str| emit_warning(format, message);
+    let program = read_sierra_program(&args.sierra, args.strict, &mut warn)?;
+    let input = read_program_input(&args.input, args.strict)?;
+    let layout = args.layout.unwrap_or(Layout::Recursive);
+    let merged = merge_program(program, input, layout)?;
+    emit_result(format, args.output.as_deref(), merged)
 }
 
-fn merge(args: MergeArgs) {
-    let sierra_program: Program =
-        serde_json::from_reader(BufReader::new(File::open(args.sierra).unwrap())).unwrap();
-    let input: serde_json::Value =
-        serde_json::from_reader(BufReader::new(File::open(args.input).unwrap())).unwrap();
+fn compile(args: CompileArgs, format: OutputFormat) -> Result<()> {
+    let program = compile_sierra_program(&args.program)?;
+    emit_result(format, args.output.as_deref(), serde_json::to_value(&program)?)
+}
 
-    let layout = args.layout.unwrap_or(Layout::Recursive);
-    let merged = serde_json::to_string(&ProgramWithArgs {
-        program: sierra_program,
-        program_input: input,
-        layout: layout.to_string(),
-    })
-    .unwrap();
-    match args.output {
-        Some(output) => {
-            let mut file = std::fs::File::create(output).unwrap();
-            file.write_all(merged.as_bytes()).unwrap();
-        }
-        None => {
-            println!("{}", merged)
-        }
-    }
+fn compile_casm(args: CompileCasmArgs, format: OutputFormat) -> Result<()> {
+    let sierra = read_json(&args.sierra, false)?;
+    let casm = compile_casm_json(sierra)?;
+    emit_result(format, args.output.as_deref(), casm)
 }
 
-fn compile(args: CompileArgs) {
-    let program = compile_sierra(&args.program);
-    let json_program = serde_json::to_string(&program).unwrap();
-    match args.output {
+/// Writes `payload` to `output` (or stdout). In [`OutputFormat::Json`] an
+/// `{"status":"ok", ...}` envelope is printed to stdout: it carries the payload
+/// inline, or only the `output_path` when the raw payload went to a file.
+fn emit_result(format: OutputFormat, output: Option<&Path>, payload: serde_json::Value) -> Result<()> {
+    match output {
         Some(output) => {
-            let mut file = std::fs::File::create(output).unwrap();
-            file.write_all(json_program.as_bytes()).unwrap();
-        }
-        None => {
-            println!("{}", json_program)
+            let mut file = File::create(output)?;
+            file.write_all(serde_json::to_string(&payload)?.as_bytes())?;
+            if let OutputFormat::Json = format {
+                println!(
+                    "{}",
+                    json!({"status": "ok", "output_path": output.display().to_string()})
+                );
+            }
         }
+        None => match format {
+            OutputFormat::Text => println!("{}", serde_json::to_string(&payload)?),
+            OutputFormat::Json => {
+                println!("{}", json!({"status": "ok", "output": payload}))
+            }
+        },
     }
+    Ok(())
 }
 
-fn compile_sierra(filename: &Path) -> Program {
-    let compiler_config = CompilerConfig {
-        replace_ids: true,
-        ..CompilerConfig::default()
-    };
-    let mut db = RootDatabase::builder()
-        .detect_corelib()
-        .skip_auto_withdraw_gas()
-        .build()
-        .unwrap();
-    let main_crate_ids = setup_project(&mut db, filename).unwrap();
-    compile_prepared_db(&mut db, main_crate_ids, compiler_config).unwrap().program
+/// Renders a non-fatal warning to stderr, matching the selected output mode so
+/// JSON consumers never see a bare text line.
+fn emit_warning(format: OutputFormat, message: &str) {
+    match format {
+        OutputFormat::Text => eprintln!("warning: {message}"),
+        OutputFormat::Json => {
+            eprintln!("{}", json!({"status": "warning", "message": message}))
+        }
+    }
 }
 
-impl fmt::Display for Layout {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            Layout::Recursive => write!(f, "recursive"),
-        }
+/// Renders an error to stderr as a bare message or a
+/// `{"status":"error", ...}` envelope.
+fn emit_error(format: OutputFormat, stage: &str, path: &Path, err: &Error) {
+    match format {
+        OutputFormat::Text => eprintln!("{err:?}"),
+        OutputFormat::Json => eprintln!(
+            "{}",
+            json!({
+                "status": "error",
+                "stage": stage,
+                "message": err.to_string(),
+                "path": path.display().to_string(),
+            })
+        ),
     }
 }